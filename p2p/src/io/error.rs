@@ -0,0 +1,23 @@
+use std::io;
+
+/// Errors that can occur while performing the handshake or exchanging
+/// framed messages with a peer.
+#[derive(Debug)]
+pub enum Error {
+	/// Underlying I/O error while reading from or writing to the peer.
+	Io(io::Error),
+	/// The peer violated the handshake protocol, e.g. sent an unexpected
+	/// message, or its advertised services do not cover what we require.
+	HandshakeFailed,
+	/// The peer's advertised protocol version is below the minimum we
+	/// support.
+	InvalidVersion,
+	/// The handshake did not complete within the configured timeout.
+	Timeout,
+}
+
+impl From<io::Error> for Error {
+	fn from(err: io::Error) -> Self {
+		Error::Io(err)
+	}
+}
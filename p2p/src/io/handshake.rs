@@ -1,37 +1,97 @@
-use std::{io, cmp};
+use std::{io, cmp, mem};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 use futures::{Future, Poll, Async};
-use net::messages::{Version, Message, Payload};
-use net::common::Magic;
+use tokio_timer::Delay;
+use net::messages::{Version, Message, Payload, SendCompact, FeeFilter};
+use net::common::{Magic, ServiceFlags};
 use io::{write_message, read_message, ReadMessage, WriteMessage, Error};
 
-pub fn handshake<A>(a: A, magic: Magic, version: Version) -> Handshake<A> where A: io::Write + io::Read {
+/// Peers below this version do not understand the post-version/pre-verack
+/// relay negotiation messages (wtxidrelay, sendaddrv2), so we must not send
+/// them until we know the peer can make sense of them.
+const FEATURE_NEGOTIATION_MIN_VERSION: u32 = 70016;
+
+pub fn handshake<A>(a: A, magic: Magic, version: Version, min_version: u32, required_services: ServiceFlags, features: HandshakeFeatures, timeout: Duration) -> Handshake<A> where A: io::Write + io::Read {
 	Handshake {
 		version: version.version(),
+		min_version: min_version,
+		required_services: required_services,
+		features: features,
 		state: HandshakeState::SendVersion(write_message(a, &version_message(magic, version))),
 		magic: magic,
+		timeout: timeout,
+		deadline: Delay::new(Instant::now() + timeout),
 	}
 }
 
-pub fn accept_handshake<A>(a: A, magic: Magic, version: Version) -> AcceptHandshake<A> where A: io::Write + io::Read {
+pub fn accept_handshake<A>(a: A, magic: Magic, version: Version, min_version: u32, required_services: ServiceFlags, features: HandshakeFeatures, timeout: Duration) -> AcceptHandshake<A> where A: io::Write + io::Read {
 	AcceptHandshake {
 		version: version.version(),
+		min_version: min_version,
+		required_services: required_services,
+		features: features,
 		state: AcceptHandshakeState::ReceiveVersion {
 			local_version: Some(version),
 			future: read_message(a, magic, 0),
 		},
 		magic: magic,
+		timeout: timeout,
+		deadline: Delay::new(Instant::now() + timeout),
 	}
 }
 
-/// TODO: return Err if other version is not supported
-pub fn negotiate_version(local: u32, other: u32) -> Result<u32, Error> {
+/// Negotiates the protocol version to use with a peer, rejecting peers that
+/// advertise a version below `min_version`.
+pub fn negotiate_version(local: u32, other: u32, min_version: u32) -> Result<u32, Error> {
+	if other < min_version {
+		return Err(Error::InvalidVersion);
+	}
+
 	Ok(cmp::min(local, other))
 }
 
+/// Checks that the peer's advertised services cover everything we require,
+/// e.g. `NODE_NETWORK` or `NODE_WITNESS`.
+fn check_required_services(version: &Version, required_services: ServiceFlags) -> Result<(), Error> {
+	if version.services().contains(required_services) {
+		Ok(())
+	} else {
+		Err(Error::HandshakeFailed)
+	}
+}
+
+/// The set of optional post-version relay features we are willing to offer
+/// a peer during the handshake.
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeFeatures {
+	pub send_headers: bool,
+	pub send_compact: bool,
+	pub send_compact_high_bandwidth: bool,
+	pub send_compact_version: u64,
+	pub wtxid_relay: bool,
+	pub addr_v2: bool,
+	pub fee_filter: Option<u64>,
+}
+
+/// The relay features the peer announced before its verack, recorded whether
+/// or not we offered them in `HandshakeFeatures`. Announcements the peer sends
+/// after its verack are not read by the handshake.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NegotiatedFeatures {
+	pub send_headers: bool,
+	pub send_compact: bool,
+	pub send_compact_high_bandwidth: bool,
+	pub send_compact_version: u64,
+	pub wtxid_relay: bool,
+	pub addr_v2: bool,
+}
+
 #[derive(Debug)]
 pub struct HandshakeResult {
 	pub version: Version,
 	pub negotiated_version: u32,
+	pub features: NegotiatedFeatures,
 }
 
 fn version_message(magic: Magic, version: Version) -> Message {
@@ -42,14 +102,105 @@ fn verack_message(magic: Magic) -> Message {
 	Message::new(magic, Payload::Verack)
 }
 
+/// Messages that must precede our own verack, honored only once we know the
+/// peer's version is recent enough to understand them (BIP 339 / BIP 155).
+fn pre_verack_messages(magic: Magic, peer_version: u32, features: &HandshakeFeatures) -> VecDeque<Message> {
+	let mut queue = VecDeque::new();
+	if peer_version < FEATURE_NEGOTIATION_MIN_VERSION {
+		return queue;
+	}
+
+	if features.wtxid_relay {
+		queue.push_back(Message::new(magic, Payload::WtxidRelay));
+	}
+	if features.addr_v2 {
+		queue.push_back(Message::new(magic, Payload::SendAddrV2));
+	}
+	queue
+}
+
+/// Messages we optionally send once the verack has gone out.
+fn post_verack_messages(magic: Magic, features: &HandshakeFeatures) -> VecDeque<Message> {
+	let mut queue = VecDeque::new();
+	if features.send_headers {
+		queue.push_back(Message::new(magic, Payload::SendHeaders));
+	}
+	if features.send_compact {
+		let send_compact = SendCompact {
+			announce: features.send_compact_high_bandwidth,
+			version: features.send_compact_version,
+		};
+		queue.push_back(Message::new(magic, Payload::SendCompact(send_compact)));
+	}
+	if let Some(fee_rate) = features.fee_filter {
+		queue.push_back(Message::new(magic, Payload::FeeFilter(FeeFilter { fee_rate: fee_rate })));
+	}
+	queue
+}
+
+/// Applies an incoming message to `features` if it is a known relay-feature
+/// announcement; any other message is left untouched.
+fn apply_feature_message(features: &mut NegotiatedFeatures, payload: &Payload) {
+	match *payload {
+		Payload::SendHeaders => {
+			features.send_headers = true;
+		},
+		Payload::SendCompact(ref send_compact) => {
+			features.send_compact = true;
+			features.send_compact_high_bandwidth = send_compact.announce;
+			features.send_compact_version = send_compact.version;
+		},
+		Payload::WtxidRelay => {
+			features.wtxid_relay = true;
+		},
+		Payload::SendAddrV2 => {
+			features.addr_v2 = true;
+		},
+		Payload::FeeFilter(_) => (),
+		_ => (),
+	}
+}
+
 enum HandshakeState<A> {
 	SendVersion(WriteMessage<A>),
 	ReceiveVersion(ReadMessage<A>),
+	SendPreVerackFeatures {
+		version: Option<Version>,
+		queue: VecDeque<Message>,
+		future: WriteMessage<A>,
+	},
+	SendVerack {
+		version: Option<Version>,
+		future: WriteMessage<A>,
+	},
 	ReceiveVerack {
 		version: Option<Version>,
+		features: NegotiatedFeatures,
 		future: ReadMessage<A>,
 	},
-	Finished,
+	SendPostVerackFeatures {
+		result: Option<HandshakeResult>,
+		queue: VecDeque<Message>,
+		future: WriteMessage<A>,
+	},
+	/// Transient placeholder used only while reclaiming the stream on timeout;
+	/// `poll` always returns immediately after producing it, so it is never
+	/// matched on again.
+	Poisoned,
+}
+
+impl<A> HandshakeState<A> {
+	fn into_stream(self) -> A {
+		match self {
+			HandshakeState::SendVersion(future) => future.into_stream(),
+			HandshakeState::ReceiveVersion(future) => future.into_stream(),
+			HandshakeState::SendPreVerackFeatures { future, .. } => future.into_stream(),
+			HandshakeState::SendVerack { future, .. } => future.into_stream(),
+			HandshakeState::ReceiveVerack { future, .. } => future.into_stream(),
+			HandshakeState::SendPostVerackFeatures { future, .. } => future.into_stream(),
+			HandshakeState::Poisoned => unreachable!("handshake state polled after its stream was reclaimed"),
+		}
+	}
 }
 
 enum AcceptHandshakeState<A> {
@@ -61,126 +212,376 @@ enum AcceptHandshakeState<A> {
 		version: Option<Version>,
 		future: WriteMessage<A>,
 	},
+	SendPreVerackFeatures {
+		version: Option<Version>,
+		queue: VecDeque<Message>,
+		future: WriteMessage<A>,
+	},
 	SendVerack {
 		version: Option<Version>,
 		future: WriteMessage<A>,
 	},
-	Finished,
+	ReceiveVerack {
+		version: Option<Version>,
+		features: NegotiatedFeatures,
+		future: ReadMessage<A>,
+	},
+	SendPostVerackFeatures {
+		result: Option<HandshakeResult>,
+		queue: VecDeque<Message>,
+		future: WriteMessage<A>,
+	},
+	/// Transient placeholder used only while reclaiming the stream on timeout;
+	/// `poll` always returns immediately after producing it, so it is never
+	/// matched on again.
+	Poisoned,
+}
+
+impl<A> AcceptHandshakeState<A> {
+	fn into_stream(self) -> A {
+		match self {
+			AcceptHandshakeState::ReceiveVersion { future, .. } => future.into_stream(),
+			AcceptHandshakeState::SendVersion { future, .. } => future.into_stream(),
+			AcceptHandshakeState::SendPreVerackFeatures { future, .. } => future.into_stream(),
+			AcceptHandshakeState::SendVerack { future, .. } => future.into_stream(),
+			AcceptHandshakeState::ReceiveVerack { future, .. } => future.into_stream(),
+			AcceptHandshakeState::SendPostVerackFeatures { future, .. } => future.into_stream(),
+			AcceptHandshakeState::Poisoned => unreachable!("handshake state polled after its stream was reclaimed"),
+		}
+	}
 }
 
 pub struct Handshake<A> {
 	state: HandshakeState<A>,
 	magic: Magic,
 	version: u32,
+	min_version: u32,
+	required_services: ServiceFlags,
+	features: HandshakeFeatures,
+	timeout: Duration,
+	deadline: Delay,
 }
 
 pub struct AcceptHandshake<A> {
 	state: AcceptHandshakeState<A>,
 	magic: Magic,
 	version: u32,
+	min_version: u32,
+	required_services: ServiceFlags,
+	features: HandshakeFeatures,
+	timeout: Duration,
+	deadline: Delay,
 }
 
 impl<A> Future for Handshake<A> where A: io::Read + io::Write {
-	type Item = (A, HandshakeResult);
+	type Item = (A, Result<HandshakeResult, Error>);
 	type Error = Error;
 
 	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-		let (next, result) = match self.state {
-			HandshakeState::SendVersion(ref mut future) => {
-				let (stream, _) = try_ready!(future.poll());
-				(HandshakeState::ReceiveVersion(read_message(stream, self.magic, 0)), Async::NotReady)
-			},
-			HandshakeState::ReceiveVersion(ref mut future) => {
-				let (stream, message) = try_ready!(future.poll());
-				let version = match message.payload {
-					Payload::Version(version) => version,
-					_ => return Err(Error::HandshakeFailed),
-				};
-
-				let next = HandshakeState::ReceiveVerack {
-					version: Some(version),
-					future: read_message(stream, self.magic, 0),
-				};
-
-				(next, Async::NotReady)
-			},
-			HandshakeState::ReceiveVerack { ref mut version, ref mut future } => {
-				let (stream, message) = try_ready!(future.poll());
-				if message.payload != Payload::Verack {
-					return Err(Error::HandshakeFailed);
-				}
-
-				let version = version.take().expect("verack must be preceded by version");
-
-				let result = HandshakeResult {
-					negotiated_version: try!(negotiate_version(self.version, version.version())),
-					version: version,
-				};
-
-				(HandshakeState::Finished, Async::Ready((stream, result)))
-			},
-			HandshakeState::Finished => panic!("poll Handshake after it's done"),
-		};
+		loop {
+			// No state transition within `timeout` means the peer is stuck or gone.
+			// Reclaim the stream from whichever sub-future is in flight so the
+			// caller can still log the peer or send a reject, same as any other
+			// handshake failure.
+			match self.deadline.poll() {
+				Ok(Async::Ready(_)) | Err(_) => {
+					let stream = mem::replace(&mut self.state, HandshakeState::Poisoned).into_stream();
+					return Ok(Async::Ready((stream, Err(Error::Timeout))));
+				},
+				Ok(Async::NotReady) => (),
+			}
+
+			let mut reset_deadline = true;
+			self.state = match self.state {
+				HandshakeState::SendVersion(ref mut future) => {
+					let (stream, _) = try_ready!(future.poll());
+					HandshakeState::ReceiveVersion(read_message(stream, self.magic, 0))
+				},
+				HandshakeState::ReceiveVersion(ref mut future) => {
+					let (stream, message) = try_ready!(future.poll());
+					let version = match message.payload {
+						Payload::Version(version) => version,
+						_ => return Ok(Async::Ready((stream, Err(Error::HandshakeFailed)))),
+					};
+
+					if let Err(err) = check_required_services(&version, self.required_services) {
+						return Ok(Async::Ready((stream, Err(err))));
+					}
+
+					let mut queue = pre_verack_messages(self.magic, version.version(), &self.features);
+					match queue.pop_front() {
+						Some(next_message) => HandshakeState::SendPreVerackFeatures {
+							version: Some(version),
+							future: write_message(stream, &next_message),
+							queue: queue,
+						},
+						None => HandshakeState::SendVerack {
+							version: Some(version),
+							future: write_message(stream, &verack_message(self.magic)),
+						},
+					}
+				},
+				HandshakeState::SendPreVerackFeatures { ref mut version, ref mut queue, ref mut future } => {
+					let (stream, _) = try_ready!(future.poll());
+					match queue.pop_front() {
+						Some(next_message) => HandshakeState::SendPreVerackFeatures {
+							version: version.take(),
+							future: write_message(stream, &next_message),
+							queue: queue.split_off(0),
+						},
+						None => HandshakeState::SendVerack {
+							version: version.take(),
+							future: write_message(stream, &verack_message(self.magic)),
+						},
+					}
+				},
+				HandshakeState::SendVerack { ref mut version, ref mut future } => {
+					let (stream, _) = try_ready!(future.poll());
+					HandshakeState::ReceiveVerack {
+						version: version.take(),
+						features: NegotiatedFeatures::default(),
+						future: read_message(stream, self.magic, 0),
+					}
+				},
+				HandshakeState::ReceiveVerack { ref mut version, ref mut features, ref mut future } => {
+					let (stream, message) = try_ready!(future.poll());
+					if message.payload == Payload::Verack {
+						let version = version.take().expect("verack must be preceded by version");
+						let negotiated_version = match negotiate_version(self.version, version.version(), self.min_version) {
+							Ok(negotiated_version) => negotiated_version,
+							Err(err) => return Ok(Async::Ready((stream, Err(err)))),
+						};
+
+						let result = HandshakeResult {
+							negotiated_version: negotiated_version,
+							version: version,
+							features: *features,
+						};
+
+						let mut queue = post_verack_messages(self.magic, &self.features);
+						match queue.pop_front() {
+							Some(next_message) => HandshakeState::SendPostVerackFeatures {
+								result: Some(result),
+								future: write_message(stream, &next_message),
+								queue: queue,
+							},
+							None => return Ok(Async::Ready((stream, Ok(result)))),
+						}
+					} else {
+						// Unknown or not-yet-recognized commands are ignored here so we
+						// stay forward-compatible with peers that interleave other
+						// messages before their verack. They don't count as progress, so
+						// we must not push the deadline out on their account -- otherwise a
+						// peer that never sends verack but keeps trickling other messages
+						// could stall the handshake forever.
+						apply_feature_message(features, &message.payload);
+						reset_deadline = false;
+						HandshakeState::ReceiveVerack {
+							version: version.take(),
+							features: *features,
+							future: read_message(stream, self.magic, 0),
+						}
+					}
+				},
+				HandshakeState::SendPostVerackFeatures { ref mut result, ref mut queue, ref mut future } => {
+					let (stream, _) = try_ready!(future.poll());
+					match queue.pop_front() {
+						Some(next_message) => HandshakeState::SendPostVerackFeatures {
+							result: result.take(),
+							future: write_message(stream, &next_message),
+							queue: queue.split_off(0),
+						},
+						None => {
+							let result = result.take().expect("post-verack features must be preceded by a result");
+							return Ok(Async::Ready((stream, Ok(result))));
+						},
+					}
+				},
+				HandshakeState::Poisoned => unreachable!("handshake state polled after its stream was reclaimed"),
+			};
 
-		self.state = next;
-		match result {
-			// by polling again, we register new future
-			Async::NotReady => self.poll(),
-			result => Ok(result)
+			if reset_deadline {
+				self.deadline = Delay::new(Instant::now() + self.timeout);
+			}
 		}
 	}
 }
 
 impl<A> Future for AcceptHandshake<A> where A: io::Read + io::Write {
-	type Item = (A, HandshakeResult);
+	type Item = (A, Result<HandshakeResult, Error>);
 	type Error = Error;
 
 	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-		let (next, result) = match self.state {
-			AcceptHandshakeState::ReceiveVersion { ref mut local_version, ref mut future } => {
-				let (stream, message) = try_ready!(future.poll());
-				let version = match message.payload {
-					Payload::Version(version) => version,
-					_ => return Err(Error::HandshakeFailed),
-				};
-
-				let local_version = local_version.take().expect("local version must be set");
-				let next = AcceptHandshakeState::SendVersion {
-					version: Some(version),
-					future: write_message(stream, &version_message(self.magic, local_version)),
-				};
-
-				(next, Async::NotReady)
-			},
-			AcceptHandshakeState::SendVersion { ref mut version, ref mut future } => {
-				let (stream, _) = try_ready!(future.poll());
-				let next = AcceptHandshakeState::SendVerack {
-					version: version.take(),
-					future: write_message(stream, &verack_message(self.magic)),
-				};
-
-				(next, Async::NotReady)
-			},
-			AcceptHandshakeState::SendVerack { ref mut version, ref mut future } => {
-				let (stream, _) = try_ready!(future.poll());
-
-				let version = version.take().expect("verack must be preceded by version");
-
-				let result = HandshakeResult {
-					negotiated_version: try!(negotiate_version(self.version, version.version())),
-					version: version,
-				};
-
-				(AcceptHandshakeState::Finished, Async::Ready((stream, result)))
-			},
-			AcceptHandshakeState::Finished => panic!("poll AcceptHandshake after it's done"),
-		};
+		loop {
+			// No state transition within `timeout` means the peer is stuck or gone.
+			// Reclaim the stream from whichever sub-future is in flight so the
+			// caller can still log the peer or send a reject, same as any other
+			// handshake failure.
+			match self.deadline.poll() {
+				Ok(Async::Ready(_)) | Err(_) => {
+					let stream = mem::replace(&mut self.state, AcceptHandshakeState::Poisoned).into_stream();
+					return Ok(Async::Ready((stream, Err(Error::Timeout))));
+				},
+				Ok(Async::NotReady) => (),
+			}
+
+			let mut reset_deadline = true;
+			self.state = match self.state {
+				AcceptHandshakeState::ReceiveVersion { ref mut local_version, ref mut future } => {
+					let (stream, message) = try_ready!(future.poll());
+					let version = match message.payload {
+						Payload::Version(version) => version,
+						_ => return Ok(Async::Ready((stream, Err(Error::HandshakeFailed)))),
+					};
+
+					if let Err(err) = check_required_services(&version, self.required_services) {
+						return Ok(Async::Ready((stream, Err(err))));
+					}
+
+					let local_version = local_version.take().expect("local version must be set");
+					AcceptHandshakeState::SendVersion {
+						version: Some(version),
+						future: write_message(stream, &version_message(self.magic, local_version)),
+					}
+				},
+				AcceptHandshakeState::SendVersion { ref mut version, ref mut future } => {
+					let (stream, _) = try_ready!(future.poll());
+					let peer_version = version.as_ref().expect("version must be set").version();
+					let mut queue = pre_verack_messages(self.magic, peer_version, &self.features);
+					match queue.pop_front() {
+						Some(next_message) => AcceptHandshakeState::SendPreVerackFeatures {
+							version: version.take(),
+							future: write_message(stream, &next_message),
+							queue: queue,
+						},
+						None => AcceptHandshakeState::SendVerack {
+							version: version.take(),
+							future: write_message(stream, &verack_message(self.magic)),
+						},
+					}
+				},
+				AcceptHandshakeState::SendPreVerackFeatures { ref mut version, ref mut queue, ref mut future } => {
+					let (stream, _) = try_ready!(future.poll());
+					match queue.pop_front() {
+						Some(next_message) => AcceptHandshakeState::SendPreVerackFeatures {
+							version: version.take(),
+							future: write_message(stream, &next_message),
+							queue: queue.split_off(0),
+						},
+						None => AcceptHandshakeState::SendVerack {
+							version: version.take(),
+							future: write_message(stream, &verack_message(self.magic)),
+						},
+					}
+				},
+				AcceptHandshakeState::SendVerack { ref mut version, ref mut future } => {
+					let (stream, _) = try_ready!(future.poll());
+					AcceptHandshakeState::ReceiveVerack {
+						version: version.take(),
+						features: NegotiatedFeatures::default(),
+						future: read_message(stream, self.magic, 0),
+					}
+				},
+				AcceptHandshakeState::ReceiveVerack { ref mut version, ref mut features, ref mut future } => {
+					let (stream, message) = try_ready!(future.poll());
+					if message.payload == Payload::Verack {
+						let version = version.take().expect("verack must be preceded by version");
+						let negotiated_version = match negotiate_version(self.version, version.version(), self.min_version) {
+							Ok(negotiated_version) => negotiated_version,
+							Err(err) => return Ok(Async::Ready((stream, Err(err)))),
+						};
+
+						let result = HandshakeResult {
+							negotiated_version: negotiated_version,
+							version: version,
+							features: *features,
+						};
+
+						let mut queue = post_verack_messages(self.magic, &self.features);
+						match queue.pop_front() {
+							Some(next_message) => AcceptHandshakeState::SendPostVerackFeatures {
+								result: Some(result),
+								future: write_message(stream, &next_message),
+								queue: queue,
+							},
+							None => return Ok(Async::Ready((stream, Ok(result)))),
+						}
+					} else {
+						// Unknown or not-yet-recognized commands are ignored here so we
+						// stay forward-compatible with peers that interleave other
+						// messages before their verack. They don't count as progress, so
+						// we must not push the deadline out on their account -- otherwise a
+						// peer that never sends verack but keeps trickling other messages
+						// could stall the handshake forever.
+						apply_feature_message(features, &message.payload);
+						reset_deadline = false;
+						AcceptHandshakeState::ReceiveVerack {
+							version: version.take(),
+							features: *features,
+							future: read_message(stream, self.magic, 0),
+						}
+					}
+				},
+				AcceptHandshakeState::SendPostVerackFeatures { ref mut result, ref mut queue, ref mut future } => {
+					let (stream, _) = try_ready!(future.poll());
+					match queue.pop_front() {
+						Some(next_message) => AcceptHandshakeState::SendPostVerackFeatures {
+							result: result.take(),
+							future: write_message(stream, &next_message),
+							queue: queue.split_off(0),
+						},
+						None => {
+							let result = result.take().expect("post-verack features must be preceded by a result");
+							return Ok(Async::Ready((stream, Ok(result))));
+						},
+					}
+				},
+				AcceptHandshakeState::Poisoned => unreachable!("handshake state polled after its stream was reclaimed"),
+			};
+
+			if reset_deadline {
+				self.deadline = Delay::new(Instant::now() + self.timeout);
+			}
+		}
+	}
+}
 
-		self.state = next;
-		match result {
-			// by polling again, we register new future
-			Async::NotReady => self.poll(),
-			result => Ok(result)
+#[cfg(test)]
+mod tests {
+	use super::{negotiate_version, apply_feature_message, NegotiatedFeatures};
+	use net::messages::Payload;
+	use io::Error;
+
+	#[test]
+	fn negotiate_version_picks_the_lower_of_the_two_versions() {
+		assert_eq!(negotiate_version(70016, 70015, 0).unwrap(), 70015);
+		assert_eq!(negotiate_version(70015, 70016, 0).unwrap(), 70015);
+	}
+
+	#[test]
+	fn negotiate_version_rejects_peers_below_the_minimum() {
+		match negotiate_version(70016, 70001, 70002) {
+			Err(Error::InvalidVersion) => (),
+			other => panic!("expected Error::InvalidVersion, got {:?}", other),
 		}
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn negotiate_version_accepts_a_peer_exactly_at_the_minimum() {
+		assert_eq!(negotiate_version(70016, 70002, 70002).unwrap(), 70002);
+	}
+
+	#[test]
+	fn apply_feature_message_records_a_recognized_announcement() {
+		let mut features = NegotiatedFeatures::default();
+		apply_feature_message(&mut features, &Payload::SendHeaders);
+		assert_eq!(features, NegotiatedFeatures { send_headers: true, ..NegotiatedFeatures::default() });
+	}
+
+	#[test]
+	fn apply_feature_message_ignores_anything_else() {
+		let mut features = NegotiatedFeatures::default();
+		apply_feature_message(&mut features, &Payload::Verack);
+		assert_eq!(features, NegotiatedFeatures::default());
+	}
+}
@@ -0,0 +1,13 @@
+mod error;
+mod handshake;
+mod read_message;
+mod write_message;
+
+pub use self::error::Error;
+pub use self::handshake::{
+	handshake, accept_handshake, negotiate_version,
+	Handshake, AcceptHandshake,
+	HandshakeFeatures, NegotiatedFeatures, HandshakeResult,
+};
+pub use self::read_message::{read_message, ReadMessage};
+pub use self::write_message::{write_message, WriteMessage};
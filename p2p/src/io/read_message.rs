@@ -0,0 +1,150 @@
+use std::io;
+use std::mem;
+use futures::{Future, Poll, Async};
+use net::messages::{Message, MessageHeader};
+use net::common::Magic;
+use io::Error;
+
+/// Reads a single framed message from `a`: the fixed-size header first,
+/// then the payload it describes.
+pub fn read_message<A>(a: A, magic: Magic, version: u32) -> ReadMessage<A> where A: io::Read {
+	ReadMessage {
+		magic: magic,
+		version: version,
+		state: ReadMessageState::Header {
+			reader: a,
+			buffer: vec![0u8; MessageHeader::LEN],
+			read: 0,
+		},
+	}
+}
+
+enum ReadMessageState<A> {
+	Header {
+		reader: A,
+		buffer: Vec<u8>,
+		read: usize,
+	},
+	Payload {
+		reader: A,
+		header: MessageHeader,
+		buffer: Vec<u8>,
+		read: usize,
+	},
+	Finished,
+}
+
+pub struct ReadMessage<A> {
+	magic: Magic,
+	version: u32,
+	state: ReadMessageState<A>,
+}
+
+impl<A> ReadMessage<A> {
+	/// Reclaims the underlying stream without waiting for the read to
+	/// finish, e.g. when the handshake driving this future has timed out
+	/// and the caller just wants to close the connection cleanly.
+	pub fn into_stream(self) -> A {
+		match self.state {
+			ReadMessageState::Header { reader, .. } => reader,
+			ReadMessageState::Payload { reader, .. } => reader,
+			ReadMessageState::Finished => panic!("into_stream called on a ReadMessage that already resolved"),
+		}
+	}
+}
+
+impl<A> Future for ReadMessage<A> where A: io::Read {
+	type Item = (A, Message);
+	type Error = Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			match mem::replace(&mut self.state, ReadMessageState::Finished) {
+				ReadMessageState::Header { mut reader, mut buffer, mut read } => {
+					while read < buffer.len() {
+						match reader.read(&mut buffer[read..]) {
+							Ok(0) => return Err(Error::from(io::Error::from(io::ErrorKind::UnexpectedEof))),
+							Ok(n) => read += n,
+							Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+								self.state = ReadMessageState::Header { reader: reader, buffer: buffer, read: read };
+								return Ok(Async::NotReady);
+							},
+							Err(err) => return Err(Error::from(err)),
+						}
+					}
+
+					let header = match MessageHeader::deserialize(&buffer) {
+						Ok(header) => header,
+						Err(err) => return Err(err),
+					};
+
+					self.state = ReadMessageState::Payload {
+						reader: reader,
+						buffer: vec![0u8; header.len as usize],
+						read: 0,
+						header: header,
+					};
+				},
+				ReadMessageState::Payload { mut reader, header, mut buffer, mut read } => {
+					while read < buffer.len() {
+						match reader.read(&mut buffer[read..]) {
+							Ok(0) => return Err(Error::from(io::Error::from(io::ErrorKind::UnexpectedEof))),
+							Ok(n) => read += n,
+							Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+								self.state = ReadMessageState::Payload { reader: reader, header: header, buffer: buffer, read: read };
+								return Ok(Async::NotReady);
+							},
+							Err(err) => return Err(Error::from(err)),
+						}
+					}
+
+					let message = match Message::deserialize(self.magic, self.version, &header, &buffer) {
+						Ok(message) => message,
+						Err(err) => return Err(err),
+					};
+
+					return Ok(Async::Ready((reader, message)));
+				},
+				ReadMessageState::Finished => panic!("poll called on a ReadMessage that already resolved"),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use futures::{Future, Async};
+	use net::common::Magic;
+	use super::{ReadMessage, ReadMessageState};
+
+	struct BlockingStream;
+
+	impl io::Read for BlockingStream {
+		fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+			Err(io::Error::from(io::ErrorKind::WouldBlock))
+		}
+	}
+
+	#[test]
+	fn into_stream_reclaims_the_stream_while_a_read_is_still_in_flight() {
+		let mut read_message = ReadMessage {
+			magic: Magic::Mainnet,
+			version: 0,
+			state: ReadMessageState::Header {
+				reader: BlockingStream,
+				buffer: vec![0u8; 24],
+				read: 0,
+			},
+		};
+
+		match read_message.poll() {
+			Ok(Async::NotReady) => (),
+			_ => panic!("expected a WouldBlock read to leave the future at Async::NotReady"),
+		}
+
+		// Even though the read never completed, the stream must still be
+		// recoverable so a caller (e.g. a timed-out handshake) can close it.
+		let _stream: BlockingStream = read_message.into_stream();
+	}
+}
@@ -0,0 +1,106 @@
+use std::io;
+use std::mem;
+use futures::{Future, Poll, Async};
+use net::messages::Message;
+use io::Error;
+
+/// Writes a single framed message to `a`, resolving to the stream once the
+/// whole message has gone out.
+pub fn write_message<A>(a: A, message: &Message) -> WriteMessage<A> where A: io::Write {
+	WriteMessage {
+		state: WriteMessageState::Writing {
+			writer: a,
+			buffer: message.serialize(),
+			written: 0,
+		},
+	}
+}
+
+enum WriteMessageState<A> {
+	Writing {
+		writer: A,
+		buffer: Vec<u8>,
+		written: usize,
+	},
+	Finished,
+}
+
+pub struct WriteMessage<A> {
+	state: WriteMessageState<A>,
+}
+
+impl<A> WriteMessage<A> {
+	/// Reclaims the underlying stream without waiting for the write to
+	/// finish, e.g. when the handshake driving this future has timed out
+	/// and the caller just wants to close the connection cleanly.
+	pub fn into_stream(self) -> A {
+		match self.state {
+			WriteMessageState::Writing { writer, .. } => writer,
+			WriteMessageState::Finished => panic!("into_stream called on a WriteMessage that already resolved"),
+		}
+	}
+}
+
+impl<A> Future for WriteMessage<A> where A: io::Write {
+	type Item = (A, ());
+	type Error = Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let (mut writer, buffer, mut written) = match mem::replace(&mut self.state, WriteMessageState::Finished) {
+			WriteMessageState::Writing { writer, buffer, written } => (writer, buffer, written),
+			WriteMessageState::Finished => panic!("poll called on a WriteMessage that already resolved"),
+		};
+
+		while written < buffer.len() {
+			match writer.write(&buffer[written..]) {
+				Ok(n) => written += n,
+				Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+					self.state = WriteMessageState::Writing { writer: writer, buffer: buffer, written: written };
+					return Ok(Async::NotReady);
+				},
+				Err(err) => return Err(Error::from(err)),
+			}
+		}
+
+		Ok(Async::Ready((writer, ())))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use futures::{Future, Async};
+	use super::{WriteMessage, WriteMessageState};
+
+	struct BlockingStream;
+
+	impl io::Write for BlockingStream {
+		fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+			Err(io::Error::from(io::ErrorKind::WouldBlock))
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn into_stream_reclaims_the_stream_while_a_write_is_still_in_flight() {
+		let mut write_message = WriteMessage {
+			state: WriteMessageState::Writing {
+				writer: BlockingStream,
+				buffer: vec![1, 2, 3, 4],
+				written: 0,
+			},
+		};
+
+		match write_message.poll() {
+			Ok(Async::NotReady) => (),
+			_ => panic!("expected a WouldBlock write to leave the future at Async::NotReady"),
+		}
+
+		// Even though the write never completed, the stream must still be
+		// recoverable so a caller (e.g. a timed-out handshake) can close it.
+		let _stream: BlockingStream = write_message.into_stream();
+	}
+}